@@ -1,5 +1,7 @@
+use async_recursion::async_recursion;
 use async_trait::async_trait;
-use std::collections::HashMap;
+use futures_util::StreamExt;
+use std::collections::{HashMap, HashSet};
 use std::i64;
 use std::sync::{Arc, Mutex};
 
@@ -8,20 +10,437 @@ use reqwest::{
     header::{AUTHORIZATION, CONTENT_TYPE, HeaderMap, HeaderValue},
 };
 use serde_json::{Value, json};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+// Default cap on tool-calling round-trips before LLMNode gives up and
+// stores whatever text the model last said (or a truncation note if none).
+const DEFAULT_MAX_STEPS: usize = 8;
+
+// Default cap on total node executions in one Graph::run, so a conditional
+// edge that loops back can't spin forever.
+const DEFAULT_STEP_BUDGET: usize = 1000;
+
+// Target name for add_edge/add_conditional_edge meaning "stop here" - it
+// names no node, so nothing runs for it and the branch simply ends.
+pub const END: &str = "__end__";
+
+// Streamed tool calls arrive as partial `function.arguments` fragments
+// indexed by position; fold each fragment into the in-progress call so the
+// end result matches the shape a non-streaming response would have sent.
+fn merge_tool_call_delta(tool_calls: &mut Vec<Value>, delta: &Value) {
+    let index = delta.get("index").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+    while tool_calls.len() <= index {
+        tool_calls.push(json!({
+            "id": "",
+            "type": "function",
+            "function": { "name": "", "arguments": "" }
+        }));
+    }
+
+    let entry = &mut tool_calls[index];
+    if let Some(id) = delta.get("id").and_then(|v| v.as_str()) {
+        entry["id"] = json!(id);
+    }
+    if let Some(function) = delta.get("function") {
+        if let Some(name) = function.get("name").and_then(|v| v.as_str()) {
+            entry["function"]["name"] = json!(name);
+        }
+        if let Some(args) = function.get("arguments").and_then(|v| v.as_str()) {
+            let existing = entry["function"]["arguments"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            entry["function"]["arguments"] = json!(format!("{}{}", existing, args));
+        }
+    }
+}
+
+// Appends freshly-received bytes to `byte_buffer` and drains as much of it
+// as decodes to valid UTF-8 into `text_buffer`. A chunk boundary is an
+// arbitrary TCP-level split and routinely falls in the middle of a
+// multi-byte character, so decoding each chunk on its own (even with
+// `from_utf8_lossy`) would corrupt it; any trailing partial character is
+// left in `byte_buffer` to be completed by a later call.
+fn append_stream_bytes(byte_buffer: &mut Vec<u8>, bytes: &[u8], text_buffer: &mut String) {
+    byte_buffer.extend_from_slice(bytes);
+
+    let valid_up_to = match std::str::from_utf8(byte_buffer) {
+        Ok(text) => text.len(),
+        Err(error) => error.valid_up_to(),
+    };
+    if valid_up_to > 0 {
+        text_buffer.push_str(std::str::from_utf8(&byte_buffer[..valid_up_to]).unwrap());
+        byte_buffer.drain(..valid_up_to);
+    }
+}
 
 // Type Alias
 pub type SharedState = Arc<Mutex<State>>;
 pub type RLLMError = Box<dyn std::error::Error + Send + Sync>;
+// Predicate a conditional edge evaluates against `State` right after its
+// `from` node finishes, to decide whether to route to `to`.
+pub type EdgePredicate = Box<dyn Fn(&State) -> bool + Send + Sync>;
+// Callback LLMNode invokes with each streamed text fragment as it arrives;
+// registered via `LLMNode::set_stream_callback`.
+pub type StreamCallback = Box<dyn Fn(&str) + Send + Sync>;
 
 // Traits
 #[async_trait]
-pub trait Node {
+pub trait Node: Send + Sync {
     async fn execute(&self, state: SharedState) -> Result<(), RLLMError>;
+
+    // Whether `Graph::run` must never let this node execute in the same
+    // wave as another node, regardless of `GraphBuilder::mark_solo`.
+    // `mark_solo` is opt-in because most `State::data` races are specific
+    // to a pair of nodes writing the same key - but `LLMNode` always reads
+    // and appends to the one `State::messages` history shared by the whole
+    // Graph, so any two `LLMNode`s in the same wave would interleave their
+    // turns into a single corrupted conversation no matter which nodes they
+    // are. `LLMNode` overrides this to `true` so that race can't happen by
+    // default; it costs those nodes the wave's parallelism, not the whole
+    // Graph's.
+    fn requires_exclusive_execution(&self) -> bool {
+        false
+    }
+}
+
+// Decouples LLMNode from any one vendor's wire format. Implementors turn
+// the shared `messages`/`tools` shape into that vendor's request body,
+// turn that vendor's response body back into a `ParsedReply`, and supply
+// whatever headers the vendor needs for auth.
+pub trait Provider: Send + Sync {
+    fn build_request(&self, messages: &[Value], tools: &[Value]) -> Value;
+    fn parse_response(&self, response: Value) -> ParsedReply;
+    fn auth_headers(&self) -> HeaderMap;
+
+    // Whether `LLMNode::set_streaming(true)` can be used with this provider.
+    // `send_streaming_request` only knows how to parse OpenAI's
+    // `choices[0].delta` SSE shape, so providers with a different streaming
+    // wire format should override this to `false` so streaming fails loudly
+    // instead of silently parsing every event to nothing.
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
+
+// A model reply normalized to a common shape regardless of provider.
+// `tool_calls`, when present, always uses the OpenAI
+// `{id, type, function: {name, arguments}}` shape so the rest of LLMNode
+// (and the tool-calling loop) doesn't need to know which provider ran.
+pub struct ParsedReply {
+    pub content: Option<String>,
+    pub tool_calls: Option<Vec<Value>>,
+}
+
+pub struct OpenAIProvider {
+    model: String,
+    api_key: String,
+}
+
+impl OpenAIProvider {
+    pub fn new(model: String, api_key: String) -> Self {
+        Self { model, api_key }
+    }
+}
+
+impl Provider for OpenAIProvider {
+    fn build_request(&self, messages: &[Value], tools: &[Value]) -> Value {
+        json!({
+            "model": &self.model,
+            "messages": messages,
+            "tools": tools
+        })
+    }
+
+    fn parse_response(&self, response: Value) -> ParsedReply {
+        let msg = &response["choices"][0]["message"];
+        ParsedReply {
+            content: msg.get("content").and_then(|v| v.as_str()).map(String::from),
+            tool_calls: msg.get("tool_calls").and_then(|v| v.as_array()).cloned(),
+        }
+    }
+
+    fn auth_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        if let Ok(value) = HeaderValue::from_str(&self.api_key) {
+            headers.insert(AUTHORIZATION, value);
+        }
+        headers
+    }
+}
+
+pub struct AnthropicProvider {
+    model: String,
+    api_key: String,
+    max_tokens: u32,
+}
+
+impl AnthropicProvider {
+    pub fn new(model: String, api_key: String) -> Self {
+        Self {
+            model,
+            api_key,
+            max_tokens: 1024,
+        }
+    }
+
+    pub fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.max_tokens = max_tokens;
+    }
+}
+
+impl Provider for AnthropicProvider {
+    fn build_request(&self, messages: &[Value], tools: &[Value]) -> Value {
+        // Anthropic has no "system" role message: it takes the system prompt
+        // in a dedicated top-level `system` field instead.
+        let system_prompt: Vec<&str> = messages
+            .iter()
+            .filter(|message| message["role"] == "system")
+            .filter_map(|message| message["content"].as_str())
+            .collect();
+
+        // Anthropic has no "tool" role: tool results ride back in a `user`
+        // message as `tool_result` content blocks. The multi-step tool loop
+        // emits one `tool` message per call in a round, so consecutive tool
+        // messages fold into a single `user` turn with multiple
+        // `tool_result` blocks rather than one `user` turn per result. Only
+        // tool results are folded: a plain `user` message that follows them
+        // stays its own turn.
+        let anthropic_messages: Vec<Value> = messages
+            .iter()
+            .filter(|message| message["role"] != "system")
+            .fold(Vec::new(), |mut acc: Vec<Value>, message| {
+                if message["role"] == "tool" {
+                    let tool_result = json!({
+                        "type": "tool_result",
+                        "tool_use_id": message["tool_call_id"],
+                        "content": message["content"]
+                    });
+                    let folds_into_prior_turn = acc
+                        .last()
+                        .map(|prior| prior["role"] == "user" && prior["content"].is_array())
+                        .unwrap_or(false);
+                    if folds_into_prior_turn {
+                        acc.last_mut().unwrap()["content"]
+                            .as_array_mut()
+                            .unwrap()
+                            .push(tool_result);
+                    } else {
+                        acc.push(json!({ "role": "user", "content": [tool_result] }));
+                    }
+                } else if let Some(tool_calls) =
+                    message.get("tool_calls").and_then(|v| v.as_array())
+                {
+                    // Assistant tool-call turns arrive in the OpenAI
+                    // `tool_calls: [{function: {name, arguments}}]` shape
+                    // (the shape `Message::assistant_tool_calls` writes);
+                    // Anthropic instead expects each call as its own
+                    // `tool_use` content block. Any text preamble alongside
+                    // the calls has to come along too, as its own leading
+                    // `text` block - `Message::assistant_tool_calls` keeps
+                    // that text in history precisely so it isn't dropped
+                    // just because the turn also requested tools.
+                    let mut content: Vec<Value> = Vec::new();
+                    if let Some(text) = message["content"].as_str() {
+                        if !text.is_empty() {
+                            content.push(json!({ "type": "text", "text": text }));
+                        }
+                    }
+                    content.extend(tool_calls.iter().map(|call| {
+                        let arguments = call
+                            .pointer("/function/arguments")
+                            .and_then(|v| v.as_str())
+                            .and_then(|args| serde_json::from_str::<Value>(args).ok())
+                            .unwrap_or(Value::Null);
+                        json!({
+                            "type": "tool_use",
+                            "id": call.get("id").cloned().unwrap_or(Value::Null),
+                            "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+                            "input": arguments
+                        })
+                    }));
+                    acc.push(json!({ "role": "assistant", "content": content }));
+                } else {
+                    acc.push(message.clone());
+                }
+                acc
+            });
+
+        let anthropic_tools: Vec<Value> = tools
+            .iter()
+            .filter_map(|tool| {
+                let function = tool.get("function")?;
+                Some(json!({
+                    "name": function.get("name")?,
+                    "description": function.get("description").cloned().unwrap_or(Value::Null),
+                    "input_schema": function.get("parameters").cloned().unwrap_or(Value::Null)
+                }))
+            })
+            .collect();
+
+        let mut request = json!({
+            "model": &self.model,
+            "max_tokens": self.max_tokens,
+            "messages": anthropic_messages,
+            "tools": anthropic_tools
+        });
+        if !system_prompt.is_empty() {
+            request["system"] = json!(system_prompt.join("\n"));
+        }
+        request
+    }
+
+    fn parse_response(&self, response: Value) -> ParsedReply {
+        let blocks = response
+            .get("content")
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        let mut content = String::new();
+        let mut tool_calls: Vec<Value> = Vec::new();
+
+        for block in blocks {
+            match block.get("type").and_then(|v| v.as_str()) {
+                Some("text") => {
+                    if let Some(text) = block.get("text").and_then(|v| v.as_str()) {
+                        content.push_str(text);
+                    }
+                }
+                Some("tool_use") => {
+                    let arguments = block.get("input").cloned().unwrap_or(Value::Null).to_string();
+                    tool_calls.push(json!({
+                        "id": block.get("id").cloned().unwrap_or(Value::Null),
+                        "type": "function",
+                        "function": {
+                            "name": block.get("name").cloned().unwrap_or(Value::Null),
+                            "arguments": arguments
+                        }
+                    }));
+                }
+                _ => {}
+            }
+        }
+
+        ParsedReply {
+            content: if content.is_empty() { None } else { Some(content) },
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+        }
+    }
+
+    fn auth_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+        if let Ok(value) = HeaderValue::from_str(&self.api_key) {
+            headers.insert("x-api-key", value);
+        }
+        headers
+    }
+
+    // Anthropic's SSE events (`content_block_delta`, etc.) don't match the
+    // OpenAI `choices[0].delta` shape `send_streaming_request` parses.
+    fn supports_streaming(&self) -> bool {
+        false
+    }
 }
 
 // Structs
 #[derive(Debug, Default)]
-pub struct State(HashMap<String, Value>);
+pub struct State {
+    data: HashMap<String, Value>,
+    messages: Vec<Message>,
+}
+
+// A single turn in a conversation. LLMNode appends to and reads this from
+// `State` so that chained LLMNodes in a Graph share one coherent history
+// instead of each starting from a blank slate.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub role: String,
+    pub content: MessageContent,
+    pub tool_call_id: Option<String>,
+    pub tool_calls: Option<Vec<Value>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum MessageContent {
+    Text(String),
+}
+
+impl Message {
+    pub fn system(content: String) -> Self {
+        Self {
+            role: "system".to_string(),
+            content: MessageContent::Text(content),
+            tool_call_id: None,
+            tool_calls: None,
+        }
+    }
+
+    pub fn user(content: String) -> Self {
+        Self {
+            role: "user".to_string(),
+            content: MessageContent::Text(content),
+            tool_call_id: None,
+            tool_calls: None,
+        }
+    }
+
+    pub fn assistant(content: String) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: MessageContent::Text(content),
+            tool_call_id: None,
+            tool_calls: None,
+        }
+    }
+
+    // `content` is `Some` when the provider returned a textual preamble
+    // alongside the tool calls (Anthropic routinely does this; OpenAI
+    // usually doesn't) - it must be kept so that text isn't dropped from
+    // history just because the turn also requested tools.
+    pub fn assistant_tool_calls(content: Option<String>, tool_calls: Vec<Value>) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: MessageContent::Text(content.unwrap_or_default()),
+            tool_call_id: None,
+            tool_calls: Some(tool_calls),
+        }
+    }
+
+    pub fn tool(tool_call_id: String, content: String) -> Self {
+        Self {
+            role: "tool".to_string(),
+            content: MessageContent::Text(content),
+            tool_call_id: Some(tool_call_id),
+            tool_calls: None,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        let mut obj = serde_json::Map::new();
+        obj.insert("role".to_string(), json!(self.role));
+        match &self.content {
+            MessageContent::Text(text) => {
+                obj.insert("content".to_string(), json!(text));
+            }
+        }
+        if let Some(tool_calls) = &self.tool_calls {
+            obj.insert("tool_calls".to_string(), json!(tool_calls));
+        }
+        if let Some(tool_call_id) = &self.tool_call_id {
+            obj.insert("tool_call_id".to_string(), json!(tool_call_id));
+        }
+        Value::Object(obj)
+    }
+}
 
 pub struct StateBuilder {
     state: SharedState,
@@ -35,21 +454,29 @@ pub struct LLMNode {
     prompt: String,
     prompt_var_list: Vec<String>,
     endpoint: String,
-    model: String,
-    api_key: String,
+    provider: Box<dyn Provider>,
+    client: Client,
     tools: HashMap<String, Tool>,
     tool_list: Vec<Value>,
+    max_steps: usize,
+    streaming: bool,
+    stream_callback: Option<StreamCallback>,
 }
 
-pub struct Graph<'a> {
-    nodes: &'a HashMap<String, Box<dyn Node>>,
-    start_edges: Vec<String>,
+pub struct Graph {
+    nodes: Arc<HashMap<String, Box<dyn Node>>>,
     adjacent_edge_map: HashMap<String, Vec<String>>,
+    conditional_edge_map: HashMap<String, Vec<(EdgePredicate, String)>>,
+    solo_nodes: HashSet<String>,
+    step_budget: usize,
 }
 
 pub struct GraphBuilder {
     nodes: HashMap<String, Box<dyn Node>>,
     edges: Vec<(String, String)>,
+    conditional_edges: Vec<(String, EdgePredicate, String)>,
+    solo_nodes: HashSet<String>,
+    step_budget: usize,
 }
 
 pub struct Tool {
@@ -73,7 +500,7 @@ impl State {
         }
     }
     fn log(&self, var_name: &str) {
-        if let Some(data) = self.0.get(var_name) {
+        if let Some(data) = self.data.get(var_name) {
             match data {
                 Value::String(s) => println!("{}", s), // no quotes
                 Value::Number(n) => println!("{}", n),
@@ -84,8 +511,23 @@ impl State {
         }
     }
 
+    // Conversation history lives on its own field rather than in `data`, so
+    // it never goes through `check_valid_key` and can't collide with a
+    // user-chosen state key (e.g. `rllm_messages`).
+    pub fn append_message(&mut self, message: Message) {
+        self.messages.push(message);
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    pub fn clear_history(&mut self) {
+        self.messages.clear();
+    }
+
     pub fn get_rllm_number(&self, var_name: &str) -> Result<i64, String> {
-        if let Some(data) = self.0.get(var_name) {
+        if let Some(data) = self.data.get(var_name) {
             if let Value::Number(n) = data {
                 let num = match n.as_i64() {
                     Some(num) => Ok(num),
@@ -101,7 +543,7 @@ impl State {
     }
 
     pub fn get_rllm_string(&self, var_name: &str) -> Result<String, String> {
-        if let Some(data) = self.0.get(var_name) {
+        if let Some(data) = self.data.get(var_name) {
             if let Value::String(s) = data {
                 Ok(s.to_string())
             } else {
@@ -113,7 +555,7 @@ impl State {
     }
 
     pub fn get_rllm_bool(&self, var_name: &str) -> Result<bool, String> {
-        if let Some(data) = self.0.get(var_name) {
+        if let Some(data) = self.data.get(var_name) {
             if let Value::Bool(b) = data {
                 Ok(*b)
             } else {
@@ -125,7 +567,7 @@ impl State {
     }
 
     pub fn get_rllm_json(&self, var_name: &str) -> Result<Value, String> {
-        if let Some(data) = self.0.get(var_name) {
+        if let Some(data) = self.data.get(var_name) {
             Ok(data.clone())
         } else {
             Err("No Entry Found".to_string())
@@ -142,7 +584,7 @@ impl State {
 
     pub fn set_rllm_number(&mut self, var_name: &str, value: i64) -> Result<(), String> {
         if Self::check_valid_key(var_name) {
-            self.0
+            self.data
                 .insert(var_name.to_string(), Value::Number(value.into()));
             Ok(())
         } else {
@@ -151,7 +593,7 @@ impl State {
     }
     pub fn set_rllm_string(&mut self, var_name: &str, value: String) -> Result<(), String> {
         if Self::check_valid_key(var_name) {
-            self.0.insert(var_name.to_string(), Value::String(value));
+            self.data.insert(var_name.to_string(), Value::String(value));
             Ok(())
         } else {
             Err("Restricted key".to_string())
@@ -159,7 +601,7 @@ impl State {
     }
     pub fn set_rllm_bool(&mut self, var_name: &str, value: bool) -> Result<(), String> {
         if Self::check_valid_key(var_name) {
-            self.0.insert(var_name.to_string(), Value::Bool(value));
+            self.data.insert(var_name.to_string(), Value::Bool(value));
             Ok(())
         } else {
             Err("Restricted key".to_string())
@@ -167,19 +609,16 @@ impl State {
     }
     pub fn set_rllm_json(&mut self, var_name: &str, value: Value) -> Result<(), String> {
         if Self::check_valid_key(var_name) {
-            self.0.insert(var_name.to_string(), value);
+            self.data.insert(var_name.to_string(), value);
             Ok(())
         } else {
             Err("Restricted key".to_string())
         }
     }
     fn set_llm_response(&mut self, value: String) {
-        self.0
+        self.data
             .insert("rllm_response".to_string(), Value::String(value));
     }
-    fn set_llm_response_json(&mut self, value: Value) {
-        self.0.insert("rllm_response".to_string(), value);
-    }
 }
 
 impl StateBuilder {
@@ -211,81 +650,47 @@ impl FunctionNode {
 #[async_trait]
 impl Node for LLMNode {
     async fn execute(&self, state: SharedState) -> Result<(), RLLMError> {
-        let client = Client::new();
-        let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        headers.insert(AUTHORIZATION, HeaderValue::from_str(&self.api_key)?);
-
         let mut prompt = self.prompt.clone();
+        let mut messages: Vec<Message> = Vec::new();
 
         match state.lock() {
-            Ok(context_state) => {
+            Ok(mut context_state) => {
                 for elem in self.prompt_var_list.iter() {
                     let data = context_state.get_rllm_string(elem)?;
                     prompt = prompt.replacen("{}", data.as_str(), 1);
                 }
+
+                messages = context_state.messages().to_vec();
+                let user_message = Message::user(prompt);
+                context_state.append_message(user_message.clone());
+                messages.push(user_message);
             }
             Err(_) => println!("Couldn't aquire lock!"),
         }
 
-        let request_body = json!({
-          "model": &self.model,
-          "messages": [{
-              "role": "user",
-              "content": prompt.as_str()
-          }],
-            "tools": self.tool_list
-        });
-
-        let res = client
-            .post(&self.endpoint)
-            .headers(headers)
-            .body(request_body.to_string())
-            .send()
-            .await?
-            .text()
-            .await?;
+        self.run_conversation(&state, messages, 0).await
+    }
 
-        let body: Value = serde_json::from_str(&res)?;
-        let msg = &body["choices"][0]["message"];
-        if let Some(tools_call) = msg.get("tool_calls") {
-            if let Some(tool_array) = tools_call.as_array() {
-                for tool in tool_array {
-                    match state.lock() {
-                        Ok(mut context_state) => {
-                            context_state.set_llm_response_json(tool.clone());
-                        }
-                        Err(_) => println!("Couldn't aquire lock!"),
-                    }
-                    if let Some(tool_name) = tool.pointer("/function/name").and_then(|v| v.as_str())
-                    {
-                        if let Some(tool_func) = self.tools.get(tool_name) {
-                            tool_func.tool_fn.execute(Arc::clone(&state)).await?;
-                        }
-                    }
-                }
-            }
-        } else {
-            match state.lock() {
-                Ok(mut context_state) => {
-                    context_state.set_llm_response(msg["content"].to_string());
-                }
-                Err(_) => println!("Couldn't aquire lock!"),
-            }
-        }
-        Ok(())
+    // See the doc comment on `Node::requires_exclusive_execution`: this
+    // node reads and appends to the Graph-wide `State::messages` history,
+    // so it can never safely share a wave with another `LLMNode`.
+    fn requires_exclusive_execution(&self) -> bool {
+        true
     }
 }
 impl LLMNode {
-    pub fn new(endpoint: String, api_key: String) -> Self {
+    pub fn new(endpoint: String, provider: Box<dyn Provider>) -> Self {
         Self {
             prompt: String::default(),
             prompt_var_list: Vec::default(),
-            model: String::default(),
-            api_key: api_key,
-            endpoint: endpoint,
+            endpoint,
+            provider,
+            client: Client::new(),
             tools: HashMap::new(),
             tool_list: Vec::new(),
+            max_steps: DEFAULT_MAX_STEPS,
+            streaming: false,
+            stream_callback: None,
         }
     }
 
@@ -294,43 +699,514 @@ impl LLMNode {
         self.prompt_var_list = var_list;
     }
 
-    pub fn set_model(&mut self, model: String) {
-        self.model = model;
-    }
-
     pub fn set_tools(&mut self, tool_list: Vec<Value>, tools: HashMap<String, Tool>) {
         self.tools = tools;
         self.tool_list = tool_list;
     }
+
+    pub fn set_max_steps(&mut self, max_steps: usize) {
+        self.max_steps = max_steps;
+    }
+
+    pub fn set_streaming(&mut self, streaming: bool) {
+        self.streaming = streaming;
+    }
+
+    pub fn set_stream_callback(&mut self, callback: StreamCallback) {
+        self.stream_callback = Some(callback);
+    }
+
+    // Posts one turn of the conversation through `self.provider` and
+    // returns its normalized reply, whether that came back as a single
+    // JSON body or was assembled token-by-token from an SSE stream.
+    async fn send_request(&self, messages: &[Value]) -> Result<ParsedReply, RLLMError> {
+        if self.streaming {
+            self.send_streaming_request(messages).await
+        } else {
+            self.send_blocking_request(messages).await
+        }
+    }
+
+    async fn send_blocking_request(&self, messages: &[Value]) -> Result<ParsedReply, RLLMError> {
+        let request_body = self.provider.build_request(messages, &self.tool_list);
+
+        let res = self
+            .client
+            .post(&self.endpoint)
+            .headers(self.provider.auth_headers())
+            .body(request_body.to_string())
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let body: Value = serde_json::from_str(&res)?;
+        Ok(self.provider.parse_response(body))
+    }
+
+    // SSE framing is currently OpenAI's `choices[0].delta` shape; providers
+    // that can't be parsed that way report it via `Provider::supports_streaming`
+    // so this errors out instead of silently returning an empty reply.
+    async fn send_streaming_request(&self, messages: &[Value]) -> Result<ParsedReply, RLLMError> {
+        if !self.provider.supports_streaming() {
+            return Err("this provider does not support streaming responses".into());
+        }
+
+        let mut request_body = self.provider.build_request(messages, &self.tool_list);
+        if let Value::Object(ref mut map) = request_body {
+            map.insert("stream".to_string(), json!(true));
+        }
+
+        let mut byte_stream = self
+            .client
+            .post(&self.endpoint)
+            .headers(self.provider.auth_headers())
+            .body(request_body.to_string())
+            .send()
+            .await?
+            .bytes_stream();
+
+        let mut byte_buffer: Vec<u8> = Vec::new();
+        let mut buffer = String::new();
+        let mut content = String::new();
+        let mut tool_calls: Vec<Value> = Vec::new();
+
+        while let Some(chunk) = byte_stream.next().await {
+            append_stream_bytes(&mut byte_buffer, &chunk?, &mut buffer);
+
+            while let Some(frame_end) = buffer.find("\n\n") {
+                let frame: String = buffer.drain(..frame_end + 2).collect();
+                let Some(data) = frame.trim().strip_prefix("data: ") else {
+                    continue;
+                };
+
+                if data == "[DONE]" {
+                    continue;
+                }
+
+                let event: Value = serde_json::from_str(data)?;
+                let delta = &event["choices"][0]["delta"];
+
+                if let Some(text) = delta.get("content").and_then(|v| v.as_str()) {
+                    content.push_str(text);
+                    if let Some(callback) = &self.stream_callback {
+                        callback(text);
+                    }
+                }
+
+                if let Some(delta_tool_calls) = delta.get("tool_calls").and_then(|v| v.as_array())
+                {
+                    for delta_call in delta_tool_calls {
+                        merge_tool_call_delta(&mut tool_calls, delta_call);
+                    }
+                }
+            }
+        }
+
+        Ok(ParsedReply {
+            content: if content.is_empty() { None } else { Some(content) },
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+        })
+    }
+
+    // Runs a single tool call: stages it under the well-known
+    // `rllm_tool_call` key, executes the registered `Tool::tool_fn`, and
+    // returns whatever it wrote to `rllm_tool_result`. `Node::execute` only
+    // receives `SharedState`, not the call that triggered it, so staging
+    // the call (including `function.arguments`) into State is the only
+    // channel a tool has to see what arguments the model chose.
+    async fn dispatch_tool_call(&self, state: &SharedState, tool: &Value) -> Result<String, RLLMError> {
+        let tool_name = tool.pointer("/function/name").and_then(|v| v.as_str());
+        match tool_name.and_then(|name| self.tools.get(name)) {
+            Some(tool_func) => {
+                if let Ok(mut context_state) = state.lock() {
+                    let _ = context_state.set_rllm_json("rllm_tool_call", tool.clone());
+                    // Drop any result left by an earlier call, so a tool that
+                    // writes nothing yields "" rather than someone else's result.
+                    context_state.data.remove("rllm_tool_result");
+                }
+                tool_func.tool_fn.execute(Arc::clone(state)).await?;
+                Ok(match state.lock() {
+                    Ok(context_state) => context_state
+                        .get_rllm_string("rllm_tool_result")
+                        .unwrap_or_default(),
+                    Err(_) => String::new(),
+                })
+            }
+            // Unregistered/typo'd tool name: don't read whatever is sitting
+            // in `rllm_tool_result` from a previous, unrelated call - that
+            // would fabricate a successful result for a tool that never ran.
+            None => Ok(format!(
+                "Error: tool \"{}\" is not registered",
+                tool_name.unwrap_or("")
+            )),
+        }
+    }
+
+    // Drives the tool-calling loop: post the conversation so far, and if the
+    // model asks for tool calls, run them, feed their results back in as
+    // `tool` messages, and go another round. Stops once the model answers
+    // without requesting tools or `max_steps` round-trips are used up.
+    #[async_recursion]
+    async fn run_conversation(
+        &self,
+        state: &SharedState,
+        mut messages: Vec<Message>,
+        step: usize,
+    ) -> Result<(), RLLMError> {
+        let wire_messages: Vec<Value> = messages.iter().map(Message::to_value).collect();
+        let reply = self.send_request(&wire_messages).await?;
+
+        if let Some(tool_array) = reply.tool_calls {
+            let assistant_message =
+                Message::assistant_tool_calls(reply.content.clone(), tool_array.clone());
+            messages.push(assistant_message.clone());
+            if let Ok(mut context_state) = state.lock() {
+                context_state.append_message(assistant_message);
+            }
+
+            for tool in &tool_array {
+                let tool_call_id = tool
+                    .get("id")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+
+                let tool_result = self.dispatch_tool_call(state, tool).await?;
+
+                let tool_message = Message::tool(tool_call_id, tool_result);
+                messages.push(tool_message.clone());
+                if let Ok(mut context_state) = state.lock() {
+                    context_state.append_message(tool_message);
+                }
+            }
+
+            if step + 1 >= self.max_steps {
+                // Out of round-trips: the reply that triggered this round asked
+                // for tools, so it is no final answer. Store whatever text it
+                // carried alongside the calls, or, if it carried none, record
+                // that the loop was truncated instead of leaving
+                // `rllm_response` stale or silently empty.
+                let reply_text = reply
+                    .content
+                    .filter(|text| !text.is_empty())
+                    .unwrap_or_else(|| {
+                        "[max_steps reached before the model gave a final reply]".to_string()
+                    });
+                match state.lock() {
+                    Ok(mut context_state) => context_state.set_llm_response(reply_text),
+                    Err(_) => println!("Couldn't aquire lock!"),
+                }
+                return Ok(());
+            }
+
+            return self.run_conversation(state, messages, step + 1).await;
+        }
+
+        let reply_text = reply.content.unwrap_or_default();
+        match state.lock() {
+            Ok(mut context_state) => {
+                context_state.append_message(Message::assistant(reply_text.clone()));
+                context_state.set_llm_response(reply_text);
+            }
+            Err(_) => println!("Couldn't aquire lock!"),
+        }
+        Ok(())
+    }
 }
 
-impl Graph<'_> {
+impl Graph {
+    // Executes the graph level by level: every node whose dependencies have
+    // all completed runs concurrently with the rest of its level, bounded
+    // by the machine's available parallelism. Because every node shares the
+    // same `SharedState`, two nodes in the same level that write the same
+    // key race each other - register such a node with
+    // `GraphBuilder::mark_solo` so it runs by itself instead. `LLMNode`s
+    // race on `State::messages` the same way regardless of which nodes they
+    // are, so they don't need `mark_solo`: `Node::requires_exclusive_execution`
+    // keeps any node that returns `true` from sharing a wave with anything else.
+    //
+    // Conditional edges are resolved against `SharedState` once a node
+    // finishes and can route back to an earlier node, so unlike plain
+    // edges a node reached this way may run more than once per call to
+    // `run`. `step_budget` caps total node executions so such a loop can't
+    // run forever; routing to `END` ends that branch instead of continuing.
+    //
+    // Forward conditional edges are an OR-join: a target becomes ready as
+    // soon as any one of its incoming conditional edges fires, not once
+    // every one of them has. That's what makes "if/else branches reconverge
+    // on a merge node" work - `in_degree` (plain edges only) still has to
+    // reach zero too, so a target with both kinds of dependency waits on
+    // whichever finishes last.
     pub async fn run(&self) -> Result<(), RLLMError> {
-        let mut visited_nodes: HashMap<String, bool> = HashMap::new();
         let shared_state = StateBuilder::new();
-        for edge in &self.start_edges {
-            if let Some(node) = self.nodes.get(edge) {
-                if let Some(_) = visited_nodes.get(edge) {
-                } else {
-                    visited_nodes.insert(edge.clone(), true);
+        let mut in_degree = self.in_degrees();
+        let forward_conditional_sources = self.forward_conditional_sources();
+        let conditional_targets: HashSet<String> =
+            forward_conditional_sources.keys().cloned().collect();
+        let mut conditional_satisfied: HashSet<String> = HashSet::new();
+        let mut executed: HashSet<String> = HashSet::new();
+        let mut ready: Vec<String> = in_degree
+            .iter()
+            .filter(|(name, degree)| **degree == 0 && !conditional_targets.contains(*name))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let parallelism = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let semaphore = Arc::new(Semaphore::new(parallelism));
+        let mut steps_used = 0usize;
+
+        while !ready.is_empty() && steps_used < self.step_budget {
+            // Only take as many nodes as the remaining budget allows this
+            // round; `Vec::drain` discards anything left un-yielded on an
+            // early `break`, which silently dropped the rest of the level
+            // (and everything downstream of it) instead of leaving it queued.
+            let remaining_budget = self.step_budget - steps_used;
+            let leftover = if ready.len() > remaining_budget {
+                ready.split_off(remaining_budget)
+            } else {
+                Vec::new()
+            };
+            let run_now = std::mem::replace(&mut ready, leftover);
+
+            let mut finished = Vec::new();
+            let mut joins = JoinSet::new();
+
+            for node_name in run_now {
+                let Some(node) = self.nodes.get(&node_name) else {
+                    continue;
+                };
+                steps_used += 1;
+
+                if self.solo_nodes.contains(&node_name) || node.requires_exclusive_execution() {
+                    // Drain every task already spawned earlier in this wave
+                    // before running the solo node inline - otherwise a
+                    // sibling spawned before it in iteration order (which,
+                    // coming from a HashMap, is non-deterministic) keeps
+                    // running concurrently with it, defeating `mark_solo`'s
+                    // (or `requires_exclusive_execution`'s) "runs by itself"
+                    // guarantee.
+                    while let Some(joined) = joins.join_next().await {
+                        joined??;
+                    }
                     node.execute(shared_state.state()).await?;
+                    finished.push(node_name);
+                    continue;
                 }
+
+                let nodes = Arc::clone(&self.nodes);
+                let state = shared_state.state();
+                let permit = Arc::clone(&semaphore);
+                let name = node_name.clone();
+                joins.spawn(async move {
+                    let _permit = permit.acquire_owned().await.expect("semaphore closed");
+                    match nodes.get(&name) {
+                        Some(node) => node.execute(state).await,
+                        None => Ok(()),
+                    }
+                });
+                finished.push(node_name);
             }
 
-            if let Some(end_edges) = self.adjacent_edge_map.get(edge) {
-                for end_edge in end_edges {
-                    if let Some(node) = self.nodes.get(end_edge) {
-                        if let Some(_) = visited_nodes.get(end_edge) {
-                        } else {
-                            visited_nodes.insert(end_edge.clone(), true);
-                            node.execute(shared_state.state()).await?;
+            while let Some(joined) = joins.join_next().await {
+                joined??;
+            }
+
+            executed.extend(finished.iter().cloned());
+
+            for node_name in &finished {
+                if let Some(targets) = self.adjacent_edge_map.get(node_name) {
+                    for target in targets {
+                        if let Some(degree) = in_degree.get_mut(target) {
+                            *degree = degree.saturating_sub(1);
+                            if *degree == 0
+                                && !ready.contains(target)
+                                && (!conditional_targets.contains(target)
+                                    || conditional_satisfied.contains(target))
+                            {
+                                ready.push(target.clone());
+                            }
+                        }
+                    }
+                }
+
+                // A node can be the `to` of more than one conditional edge
+                // that both evaluate true in the same wave (a fan-in/merge
+                // pattern), or of both a plain edge and a conditional edge -
+                // without the `!ready.contains` guard it would be pushed
+                // twice and run twice concurrently in that wave.
+                if let Some(routes) = self.conditional_edge_map.get(node_name) {
+                    if let Ok(context_state) = shared_state.state().lock() {
+                        for (predicate, target) in routes {
+                            if target.as_str() == END || !predicate(&context_state) {
+                                continue;
+                            }
+
+                            // A loop-back edge (`target` already reaches
+                            // `node_name` through some other edge) isn't counted
+                            // in `in_degree` at all - the only way in is this
+                            // very edge, so it goes straight to `ready`.
+                            // A forward edge is an OR-join against its
+                            // siblings in `conditional_targets`: the first
+                            // one to fire satisfies it, it doesn't need
+                            // every forward conditional edge into `target`
+                            // to fire the way plain edges need every
+                            // predecessor to finish. It still has to wait
+                            // on `in_degree` reaching zero, so a target with
+                            // an unfinished plain-edge dependency can't be
+                            // scheduled just because a forward conditional
+                            // edge into it fired first.
+                            if self.reaches(target, node_name) {
+                                if !ready.contains(target) {
+                                    ready.push(target.clone());
+                                }
+                            } else {
+                                conditional_satisfied.insert(target.clone());
+                                let plain_edges_done =
+                                    in_degree.get(target).is_none_or(|degree| *degree == 0);
+                                if plain_edges_done && !ready.contains(target) {
+                                    ready.push(target.clone());
+                                }
+                            }
                         }
                     }
                 }
             }
         }
+
+        if !ready.is_empty() {
+            return Err(format!(
+                "Graph::run exhausted step_budget ({}) with node(s) still queued: {:?}",
+                self.step_budget, ready
+            )
+            .into());
+        }
+
+        // A node with a plain-edge dependency that never hit zero can't be
+        // explained by a conditional branch simply not firing - a node
+        // only reachable through a conditional edge has no plain-edge
+        // in-degree to get stuck on in the first place (see
+        // `graph_conditional_edge_to_end_stops_the_branch`). Left-over
+        // in-degree here means a plain-edge cycle with no entry point, or
+        // an unreachable subgraph - the topological sort could never have
+        // finished, so `ready` emptying out is a deadlock, not completion.
+        let mut stuck: Vec<&String> = in_degree
+            .iter()
+            .filter(|(_, degree)| **degree > 0)
+            .map(|(name, _)| name)
+            .collect();
+
+        // A forward conditional target with its plain edges satisfied
+        // (`in_degree == 0`) but never added to `ready` is only a
+        // legitimate "branch not taken" if every node that could have
+        // routed to it actually got to run and decided not to - e.g.
+        // `finish` in `graph_conditional_edge_to_end_stops_the_branch`,
+        // whose only source is `start`, which always runs. If any of its
+        // sources never ran either, the routing decision was never made
+        // at all, which is the same silent no-op this function is
+        // supposed to catch, not a deliberate skip.
+        for (target, sources) in &forward_conditional_sources {
+            if conditional_satisfied.contains(target) {
+                continue;
+            }
+            if in_degree.get(target).is_some_and(|degree| *degree > 0) {
+                continue;
+            }
+            if sources.iter().any(|source| !executed.contains(source)) {
+                stuck.push(target);
+            }
+        }
+
+        if !stuck.is_empty() {
+            return Err(format!(
+                "Graph::run deadlocked: node(s) {:?} have unsatisfied dependencies \
+                 that can never be met - check for a cycle with no entry point",
+                stuck
+            )
+            .into());
+        }
+
         Ok(())
     }
+
+    // Plain-edge in-degree only (an AND-join: every predecessor has to
+    // finish). Forward conditional edges are handled separately by
+    // `forward_conditional_sources` since they're an OR-join instead - see
+    // `run`'s doc comment.
+    fn in_degrees(&self) -> HashMap<String, usize> {
+        let mut in_degree: HashMap<String, usize> =
+            self.nodes.keys().map(|name| (name.clone(), 0)).collect();
+        for targets in self.adjacent_edge_map.values() {
+            for target in targets {
+                *in_degree.entry(target.clone()).or_insert(0) += 1;
+            }
+        }
+        in_degree
+    }
+
+    // Maps each target of a forward (non-loop) conditional edge to the
+    // `from` nodes of every such edge into it - nodes that have to wait
+    // for at least one of those sources to actually route to them before
+    // `run` can schedule them, on top of whatever plain-edge dependencies
+    // `in_degrees` already tracks. An edge only lands here if it isn't
+    // closing a loop (its target can't already reach `from` through some
+    // other edge, e.g. a retry node routing back to the step that feeds
+    // it) - a loop-back target has to stay an initial root, since the
+    // only way in is the very conditional edge that hasn't run yet.
+    // Without this, a node reachable *only* by a forward conditional edge
+    // (the "route to a node further down the graph" case, as opposed to a
+    // loop-back) would run immediately, before the node that's supposed
+    // to route to it.
+    fn forward_conditional_sources(&self) -> HashMap<String, HashSet<String>> {
+        let mut sources: HashMap<String, HashSet<String>> = HashMap::new();
+        for (from, routes) in &self.conditional_edge_map {
+            for (_, to) in routes {
+                if to != END && !self.reaches(to, from) {
+                    sources.entry(to.clone()).or_default().insert(from.clone());
+                }
+            }
+        }
+        sources
+    }
+
+    // Whether `start` can reach `target` by following plain or conditional
+    // edges - used by `forward_conditional_sources` to tell a loop-closing
+    // conditional edge from a forward one (see its doc comment). This has
+    // to consider conditional edges too, not just plain ones: two nodes
+    // linked *only* by conditional edges (e.g. `a --(true)--> b` and
+    // `b --(true)--> a`, with no plain edge between them at all) are each
+    // other's only way in, exactly like a single node's self-loop - plain
+    // edges alone can't see that and would misclassify both directions as
+    // forward, permanently gating each on the other and leaving neither
+    // schedulable.
+    fn reaches(&self, start: &str, target: &str) -> bool {
+        if start == target {
+            return true;
+        }
+        let mut seen = HashSet::new();
+        let mut stack = vec![start.to_string()];
+        while let Some(node) = stack.pop() {
+            if !seen.insert(node.clone()) {
+                continue;
+            }
+            if node == target {
+                return true;
+            }
+            if let Some(targets) = self.adjacent_edge_map.get(&node) {
+                stack.extend(targets.iter().cloned());
+            }
+            if let Some(routes) = self.conditional_edge_map.get(&node) {
+                stack.extend(routes.iter().map(|(_, to)| to.clone()));
+            }
+        }
+        false
+    }
 }
 
 impl GraphBuilder {
@@ -338,6 +1214,9 @@ impl GraphBuilder {
         Self {
             nodes: HashMap::new(),
             edges: Vec::new(),
+            conditional_edges: Vec::new(),
+            solo_nodes: HashSet::new(),
+            step_budget: DEFAULT_STEP_BUDGET,
         }
     }
 
@@ -349,25 +1228,63 @@ impl GraphBuilder {
         self.edges.push(edge);
     }
 
-    fn build_adjacent_edge(&self) -> (Vec<String>, HashMap<String, Vec<String>>) {
+    // Routes `from` to `to` only when `predicate` returns true for the
+    // State as it stands right after `from` finishes. `to` may be an
+    // earlier node - combined with `set_step_budget` this is how retry /
+    // refinement loops are built - or `END` to stop that branch.
+    pub fn add_conditional_edge(
+        &mut self,
+        from: String,
+        predicate: EdgePredicate,
+        to: String,
+    ) {
+        self.conditional_edges.push((from, predicate, to));
+    }
+
+    // Opts a node out of concurrent execution. Use this for nodes that
+    // write a shared State key also written by a sibling in the same
+    // level - Graph::run otherwise runs same-level nodes in parallel and
+    // such writes would race. Nodes whose race is inherent to what they are
+    // (like `LLMNode`, which always shares `State::messages`) don't need
+    // this - they opt out on their own via `Node::requires_exclusive_execution`.
+    pub fn mark_solo(&mut self, node_name: String) {
+        self.solo_nodes.insert(node_name);
+    }
+
+    // Caps total node executions in one Graph::run. Defaults to
+    // DEFAULT_STEP_BUDGET; raise it for graphs with long conditional loops.
+    pub fn set_step_budget(&mut self, step_budget: usize) {
+        self.step_budget = step_budget;
+    }
+
+    fn build_adjacent_edge(&self) -> HashMap<String, Vec<String>> {
         let mut adjacent_edge_map: HashMap<String, Vec<String>> = HashMap::new();
-        let mut start_edges: Vec<String> = Vec::new();
         for edge in &self.edges {
-            start_edges.push(edge.0.clone());
             adjacent_edge_map
                 .entry(edge.0.clone())
                 .or_insert_with(Vec::new)
                 .push(edge.1.clone());
         }
-        (start_edges, adjacent_edge_map)
+        adjacent_edge_map
     }
 
-    pub fn build(&self) -> Graph {
-        let (start_edges, adjacent_edge_map) = self.build_adjacent_edge();
+    pub fn build(self) -> Graph {
+        let adjacent_edge_map = self.build_adjacent_edge();
+        let mut conditional_edge_map: HashMap<String, Vec<(EdgePredicate, String)>> =
+            HashMap::new();
+        for (from, predicate, to) in self.conditional_edges {
+            conditional_edge_map
+                .entry(from)
+                .or_insert_with(Vec::new)
+                .push((predicate, to));
+        }
+
         Graph {
-            nodes: &self.nodes,
-            start_edges: start_edges,
-            adjacent_edge_map: adjacent_edge_map,
+            nodes: Arc::new(self.nodes),
+            adjacent_edge_map,
+            conditional_edge_map,
+            solo_nodes: self.solo_nodes,
+            step_budget: self.step_budget,
         }
     }
 }
@@ -408,3 +1325,729 @@ impl ToolRegistry {
         self.tools.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_tool_call_delta_assembles_fragmented_arguments() {
+        let mut tool_calls = Vec::new();
+        merge_tool_call_delta(
+            &mut tool_calls,
+            &json!({"index": 0, "id": "call_1", "function": {"name": "get_weather", "arguments": "{\"city\":"}}),
+        );
+        merge_tool_call_delta(
+            &mut tool_calls,
+            &json!({"index": 0, "function": {"arguments": "\"nyc\"}"}}),
+        );
+
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0]["id"], "call_1");
+        assert_eq!(tool_calls[0]["function"]["name"], "get_weather");
+        assert_eq!(tool_calls[0]["function"]["arguments"], "{\"city\":\"nyc\"}");
+    }
+
+    #[test]
+    fn merge_tool_call_delta_handles_out_of_order_indices() {
+        let mut tool_calls = Vec::new();
+        merge_tool_call_delta(
+            &mut tool_calls,
+            &json!({"index": 1, "id": "call_2", "function": {"name": "second"}}),
+        );
+
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0]["id"], "");
+        assert_eq!(tool_calls[1]["id"], "call_2");
+        assert_eq!(tool_calls[1]["function"]["name"], "second");
+    }
+
+    #[test]
+    fn append_stream_bytes_reassembles_a_multi_byte_char_split_across_chunks() {
+        // 'é' is the two-byte UTF-8 sequence 0xC3 0xA9; split "café" so the
+        // boundary falls inside it, the way arbitrary TCP-level chunking
+        // routinely does.
+        let full = "café".as_bytes();
+        let (chunk1, chunk2) = full.split_at(full.len() - 1);
+
+        let mut byte_buffer = Vec::new();
+        let mut text_buffer = String::new();
+        append_stream_bytes(&mut byte_buffer, chunk1, &mut text_buffer);
+        assert_eq!(text_buffer, "caf");
+        assert!(!byte_buffer.is_empty());
+
+        append_stream_bytes(&mut byte_buffer, chunk2, &mut text_buffer);
+        assert_eq!(text_buffer, "café");
+        assert!(byte_buffer.is_empty());
+    }
+
+    #[test]
+    fn message_to_value_plain_text() {
+        let value = Message::user("hi there".to_string()).to_value();
+        assert_eq!(value["role"], "user");
+        assert_eq!(value["content"], "hi there");
+        assert!(value.get("tool_calls").is_none());
+        assert!(value.get("tool_call_id").is_none());
+    }
+
+    #[test]
+    fn message_to_value_assistant_tool_calls() {
+        let calls = vec![json!({"id": "call_1", "type": "function", "function": {"name": "f", "arguments": "{}"}})];
+        let value = Message::assistant_tool_calls(None, calls.clone()).to_value();
+        assert_eq!(value["role"], "assistant");
+        assert_eq!(value["content"], "");
+        assert_eq!(value["tool_calls"], json!(calls));
+    }
+
+    #[test]
+    fn message_to_value_assistant_tool_calls_keeps_text_preamble() {
+        let calls = vec![json!({"id": "call_1", "type": "function", "function": {"name": "f", "arguments": "{}"}})];
+        let value =
+            Message::assistant_tool_calls(Some("let me check".to_string()), calls).to_value();
+        assert_eq!(value["content"], "let me check");
+    }
+
+    #[test]
+    fn message_to_value_tool_result() {
+        let value = Message::tool("call_1".to_string(), "42".to_string()).to_value();
+        assert_eq!(value["role"], "tool");
+        assert_eq!(value["content"], "42");
+        assert_eq!(value["tool_call_id"], "call_1");
+    }
+
+    #[test]
+    fn anthropic_build_request_moves_system_message_to_top_level() {
+        let provider = AnthropicProvider::new("claude".to_string(), "key".to_string());
+        let messages = vec![
+            Message::system("be terse".to_string()).to_value(),
+            Message::user("hi".to_string()).to_value(),
+        ];
+
+        let request = provider.build_request(&messages, &[]);
+
+        assert_eq!(request["system"], "be terse");
+        let wire_messages = request["messages"].as_array().unwrap();
+        assert_eq!(wire_messages.len(), 1);
+        assert_eq!(wire_messages[0]["role"], "user");
+    }
+
+    #[test]
+    fn anthropic_build_request_converts_assistant_tool_calls_to_tool_use_blocks() {
+        let provider = AnthropicProvider::new("claude".to_string(), "key".to_string());
+        let tool_calls = vec![json!({
+            "id": "call_1",
+            "type": "function",
+            "function": {"name": "get_weather", "arguments": "{\"city\":\"nyc\"}"}
+        })];
+        let messages = vec![Message::assistant_tool_calls(None, tool_calls).to_value()];
+
+        let request = provider.build_request(&messages, &[]);
+
+        let wire_messages = request["messages"].as_array().unwrap();
+        assert_eq!(wire_messages.len(), 1);
+        assert_eq!(wire_messages[0]["role"], "assistant");
+        let blocks = wire_messages[0]["content"].as_array().unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0]["type"], "tool_use");
+        assert_eq!(blocks[0]["id"], "call_1");
+        assert_eq!(blocks[0]["name"], "get_weather");
+        assert_eq!(blocks[0]["input"], json!({"city": "nyc"}));
+    }
+
+    #[test]
+    fn anthropic_build_request_keeps_text_preamble_alongside_tool_use_blocks() {
+        let provider = AnthropicProvider::new("claude".to_string(), "key".to_string());
+        let tool_calls = vec![json!({
+            "id": "call_1",
+            "type": "function",
+            "function": {"name": "get_weather", "arguments": "{\"city\":\"nyc\"}"}
+        })];
+        let messages = vec![
+            Message::assistant_tool_calls(Some("let me check the weather".to_string()), tool_calls)
+                .to_value(),
+        ];
+
+        let request = provider.build_request(&messages, &[]);
+
+        let wire_messages = request["messages"].as_array().unwrap();
+        let blocks = wire_messages[0]["content"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["type"], "text");
+        assert_eq!(blocks[0]["text"], "let me check the weather");
+        assert_eq!(blocks[1]["type"], "tool_use");
+    }
+
+    #[test]
+    fn anthropic_build_request_converts_tool_result_messages() {
+        let provider = AnthropicProvider::new("claude".to_string(), "key".to_string());
+        let messages = vec![Message::tool("call_1".to_string(), "sunny".to_string()).to_value()];
+
+        let request = provider.build_request(&messages, &[]);
+
+        let wire_messages = request["messages"].as_array().unwrap();
+        assert_eq!(wire_messages[0]["role"], "user");
+        let blocks = wire_messages[0]["content"].as_array().unwrap();
+        assert_eq!(blocks[0]["type"], "tool_result");
+        assert_eq!(blocks[0]["tool_use_id"], "call_1");
+        assert_eq!(blocks[0]["content"], "sunny");
+    }
+
+    #[test]
+    fn anthropic_build_request_folds_consecutive_tool_results_into_one_user_turn() {
+        let provider = AnthropicProvider::new("claude".to_string(), "key".to_string());
+        let messages = vec![
+            Message::tool("call_1".to_string(), "sunny".to_string()).to_value(),
+            Message::tool("call_2".to_string(), "72F".to_string()).to_value(),
+            Message::user("thanks".to_string()).to_value(),
+        ];
+
+        let request = provider.build_request(&messages, &[]);
+
+        let wire_messages = request["messages"].as_array().unwrap();
+        assert_eq!(wire_messages.len(), 2);
+        assert_eq!(wire_messages[0]["role"], "user");
+        let blocks = wire_messages[0]["content"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["tool_use_id"], "call_1");
+        assert_eq!(blocks[1]["tool_use_id"], "call_2");
+        assert_eq!(wire_messages[1]["role"], "user");
+        assert_eq!(wire_messages[1]["content"], "thanks");
+    }
+
+    #[test]
+    fn anthropic_parse_response_splits_text_and_tool_use_blocks() {
+        let provider = AnthropicProvider::new("claude".to_string(), "key".to_string());
+        let response = json!({
+            "content": [
+                {"type": "text", "text": "let me check"},
+                {"type": "tool_use", "id": "call_1", "name": "get_weather", "input": {"city": "nyc"}}
+            ]
+        });
+
+        let reply = provider.parse_response(response);
+
+        assert_eq!(reply.content.as_deref(), Some("let me check"));
+        let tool_calls = reply.tool_calls.unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0]["function"]["name"], "get_weather");
+    }
+
+    #[test]
+    fn anthropic_does_not_support_streaming() {
+        let provider = AnthropicProvider::new("claude".to_string(), "key".to_string());
+        assert!(!provider.supports_streaming());
+    }
+
+    #[test]
+    fn openai_supports_streaming_by_default() {
+        let provider = OpenAIProvider::new("gpt".to_string(), "key".to_string());
+        assert!(provider.supports_streaming());
+    }
+
+    #[test]
+    fn llm_node_requires_exclusive_execution() {
+        let node = LLMNode::new(
+            "http://localhost".to_string(),
+            Box::new(OpenAIProvider::new("gpt".to_string(), "key".to_string())),
+        );
+        assert!(node.requires_exclusive_execution());
+    }
+
+    #[test]
+    fn function_node_does_not_require_exclusive_execution_by_default() {
+        let node = FunctionNode::new(Box::new(|_| Ok(())));
+        assert!(!node.requires_exclusive_execution());
+    }
+
+    #[tokio::test]
+    async fn dispatch_tool_call_lets_a_tool_read_its_own_arguments() {
+        let mut node = LLMNode::new(
+            "http://localhost".to_string(),
+            Box::new(OpenAIProvider::new("gpt".to_string(), "key".to_string())),
+        );
+        let echo_args = FunctionNode::new(Box::new(|state| {
+            let mut context_state = state.lock().unwrap();
+            let call = context_state.get_rllm_json("rllm_tool_call").unwrap();
+            let arguments = call
+                .pointer("/function/arguments")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string();
+            context_state
+                .set_rllm_string("rllm_tool_result", arguments)
+                .unwrap();
+            Ok(())
+        }));
+        let mut tools = HashMap::new();
+        tools.insert(
+            "echo".to_string(),
+            Tool::new("echo".to_string(), echo_args),
+        );
+        node.set_tools(Vec::new(), tools);
+
+        let state = StateBuilder::new();
+        let call = json!({
+            "id": "call_1",
+            "type": "function",
+            "function": {"name": "echo", "arguments": "{\"city\":\"nyc\"}"}
+        });
+
+        let result = node.dispatch_tool_call(&state.state(), &call).await.unwrap();
+
+        assert_eq!(result, "{\"city\":\"nyc\"}");
+    }
+
+    #[tokio::test]
+    async fn dispatch_tool_call_reports_unregistered_tool_names() {
+        let node = LLMNode::new(
+            "http://localhost".to_string(),
+            Box::new(OpenAIProvider::new("gpt".to_string(), "key".to_string())),
+        );
+        let state = StateBuilder::new();
+        let call = json!({"id": "call_1", "type": "function", "function": {"name": "missing", "arguments": "{}"}});
+
+        let result = node.dispatch_tool_call(&state.state(), &call).await.unwrap();
+
+        assert_eq!(result, "Error: tool \"missing\" is not registered");
+    }
+
+    fn noop_node() -> Box<dyn Node> {
+        Box::new(FunctionNode::new(Box::new(|_| Ok(()))))
+    }
+
+    #[test]
+    fn graph_in_degrees_counts_incoming_edges() {
+        let mut builder = GraphBuilder::new();
+        builder.add_node("a".to_string(), noop_node());
+        builder.add_node("b".to_string(), noop_node());
+        builder.add_node("c".to_string(), noop_node());
+        builder.add_edge(("a".to_string(), "c".to_string()));
+        builder.add_edge(("b".to_string(), "c".to_string()));
+        let graph = builder.build();
+
+        let in_degree = graph.in_degrees();
+        assert_eq!(in_degree["a"], 0);
+        assert_eq!(in_degree["b"], 0);
+        assert_eq!(in_degree["c"], 2);
+    }
+
+    #[tokio::test]
+    async fn graph_run_reports_error_instead_of_silently_dropping_nodes_at_step_budget() {
+        // Two independent root nodes are ready in the same wave; a budget of
+        // 1 can only run one of them. Graph::run must surface that the other
+        // was left queued rather than finishing Ok(()) as if it ran.
+        let mut builder = GraphBuilder::new();
+        builder.add_node("a".to_string(), noop_node());
+        builder.add_node("b".to_string(), noop_node());
+        builder.set_step_budget(1);
+        let graph = builder.build();
+
+        let result = graph.run().await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn graph_run_reports_error_for_a_plain_edge_cycle_with_no_entry_point() {
+        // "a" and "b" only reach each other, so neither ever starts with
+        // in-degree zero - `ready` is empty from the first iteration and
+        // the loop body never runs at all. Graph::run must report that
+        // deadlock instead of returning Ok(()) having executed nothing.
+        let mut builder = GraphBuilder::new();
+        builder.add_node("a".to_string(), noop_node());
+        builder.add_node("b".to_string(), noop_node());
+        builder.add_edge(("a".to_string(), "b".to_string()));
+        builder.add_edge(("b".to_string(), "a".to_string()));
+        let graph = builder.build();
+
+        let result = graph.run().await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn graph_run_reports_error_for_a_forward_conditional_target_whose_source_never_ran() {
+        // "y" and "z" form a plain-edge cycle with no entry point, so
+        // neither ever runs. "x" has no plain-edge dependency of its own
+        // (in-degree zero) but is only reachable through "y"'s conditional
+        // edge - since "y" never runs, that routing decision is never
+        // made, so "x" is just as deadlocked as "y" and "z" even though
+        // the plain in-degree check alone wouldn't catch it.
+        let mut builder = GraphBuilder::new();
+        builder.add_node("y".to_string(), noop_node());
+        builder.add_node("z".to_string(), noop_node());
+        builder.add_node("x".to_string(), noop_node());
+        builder.add_edge(("y".to_string(), "z".to_string()));
+        builder.add_edge(("z".to_string(), "y".to_string()));
+        builder.add_conditional_edge(
+            "y".to_string(),
+            Box::new(|_state: &State| true),
+            "x".to_string(),
+        );
+        let graph = builder.build();
+
+        let result = graph.run().await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn graph_conditional_edge_loops_back_to_a_prior_node_until_predicate_is_false() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_clone = Arc::clone(&runs);
+        let revise = FunctionNode::new(Box::new(move |state| {
+            let mut context_state = state.lock().unwrap();
+            let count = context_state.get_rllm_number("revisions").unwrap_or(0);
+            context_state.set_rllm_number("revisions", count + 1).unwrap();
+            runs_clone.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }));
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node("revise".to_string(), Box::new(revise));
+        builder.add_conditional_edge(
+            "revise".to_string(),
+            Box::new(|state: &State| state.get_rllm_number("revisions").unwrap_or(0) < 3),
+            "revise".to_string(),
+        );
+        let graph = builder.build();
+
+        graph.run().await.unwrap();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn graph_conditional_edge_to_end_stops_the_branch() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let ran_finish = Arc::new(AtomicBool::new(false));
+        let ran_finish_clone = Arc::clone(&ran_finish);
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node("start".to_string(), noop_node());
+        builder.add_node(
+            "finish".to_string(),
+            Box::new(FunctionNode::new(Box::new(move |_| {
+                ran_finish_clone.store(true, Ordering::SeqCst);
+                Ok(())
+            }))),
+        );
+        // Two routes out of "start": one to END that the true predicate
+        // takes, one to "finish" that it doesn't - so "finish" is only
+        // reachable at all through the route this test expects to be
+        // skipped.
+        builder.add_conditional_edge(
+            "start".to_string(),
+            Box::new(|_state: &State| true),
+            END.to_string(),
+        );
+        builder.add_conditional_edge(
+            "start".to_string(),
+            Box::new(|_state: &State| false),
+            "finish".to_string(),
+        );
+        let graph = builder.build();
+
+        graph.run().await.unwrap();
+
+        assert!(!ran_finish.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn graph_conditional_routes_to_same_target_run_only_once_per_wave() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_clone = Arc::clone(&runs);
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node("a".to_string(), noop_node());
+        builder.add_node("b".to_string(), noop_node());
+        builder.add_node(
+            "merge".to_string(),
+            Box::new(FunctionNode::new(Box::new(move |_| {
+                runs_clone.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }))),
+        );
+        builder.add_conditional_edge(
+            "a".to_string(),
+            Box::new(|_state: &State| true),
+            "merge".to_string(),
+        );
+        builder.add_conditional_edge(
+            "b".to_string(),
+            Box::new(|_state: &State| true),
+            "merge".to_string(),
+        );
+        let graph = builder.build();
+
+        graph.run().await.unwrap();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn graph_conditional_merge_runs_when_only_one_branch_routes_to_it() {
+        // "a" and "b" are mutually exclusive branches (the usual if/else
+        // shape) that both conditionally route to "merge". Only "a"'s
+        // predicate fires - "merge" must still run once "a" routes to it
+        // rather than waiting forever on "b"'s edge, which never does.
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_clone = Arc::clone(&runs);
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node("a".to_string(), noop_node());
+        builder.add_node("b".to_string(), noop_node());
+        builder.add_node(
+            "merge".to_string(),
+            Box::new(FunctionNode::new(Box::new(move |_| {
+                runs_clone.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }))),
+        );
+        builder.add_conditional_edge(
+            "a".to_string(),
+            Box::new(|_state: &State| true),
+            "merge".to_string(),
+        );
+        builder.add_conditional_edge(
+            "b".to_string(),
+            Box::new(|_state: &State| false),
+            "merge".to_string(),
+        );
+        let graph = builder.build();
+
+        graph.run().await.unwrap();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn graph_conditional_mutual_cycle_with_no_plain_edges_actually_runs() {
+        // "a" and "b" are linked *only* by conditional edges, in both
+        // directions, with no plain edges and no other root at all - each
+        // is the other's sole way in, structurally identical to a single
+        // node's self-loop. Classifying forward-vs-loopback by plain edges
+        // alone can't see that and misclassifies both directions as
+        // forward, permanently gating each on the other so neither is ever
+        // schedulable - `run` must still actually execute them.
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let a_runs = Arc::new(AtomicUsize::new(0));
+        let a_runs_clone = Arc::clone(&a_runs);
+        let a = FunctionNode::new(Box::new(move |_| {
+            a_runs_clone.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }));
+
+        let b_runs = Arc::new(AtomicUsize::new(0));
+        let b_runs_clone = Arc::clone(&b_runs);
+        let b = FunctionNode::new(Box::new(move |_| {
+            b_runs_clone.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }));
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node("a".to_string(), Box::new(a));
+        builder.add_node("b".to_string(), Box::new(b));
+        builder.add_conditional_edge(
+            "a".to_string(),
+            Box::new(|_state: &State| true),
+            "b".to_string(),
+        );
+        builder.add_conditional_edge(
+            "b".to_string(),
+            Box::new(|_state: &State| true),
+            "a".to_string(),
+        );
+        // Both predicates are always true, so this would spin forever -
+        // a small budget just keeps the test fast; the thing under test is
+        // that each side gets to run at all, not how the loop ends.
+        builder.set_step_budget(4);
+        let graph = builder.build();
+
+        let _ = graph.run().await;
+
+        assert!(a_runs.load(Ordering::SeqCst) > 0);
+        assert!(b_runs.load(Ordering::SeqCst) > 0);
+    }
+
+    #[tokio::test]
+    async fn graph_conditional_target_runs_despite_an_unrelated_conditional_cycle_pointing_at_it() {
+        // "a"'s only real dependency is the plain edge from "start",
+        // already satisfied once "start" finishes - it shouldn't matter
+        // that "a" is also the target of "b"'s conditional edge, in an
+        // otherwise unrelated a/b conditional cycle whose predicates never
+        // even fire.
+        use std::sync::atomic::Ordering;
+
+        let ran_a = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let ran_a_clone = Arc::clone(&ran_a);
+        let a = FunctionNode::new(Box::new(move |_| {
+            ran_a_clone.store(true, Ordering::SeqCst);
+            Ok(())
+        }));
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node("start".to_string(), noop_node());
+        builder.add_node("a".to_string(), Box::new(a));
+        builder.add_node("b".to_string(), noop_node());
+        builder.add_edge(("start".to_string(), "a".to_string()));
+        builder.add_conditional_edge(
+            "a".to_string(),
+            Box::new(|_state: &State| false),
+            "b".to_string(),
+        );
+        builder.add_conditional_edge(
+            "b".to_string(),
+            Box::new(|_state: &State| false),
+            "a".to_string(),
+        );
+        let graph = builder.build();
+
+        graph.run().await.unwrap();
+
+        assert!(ran_a.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn graph_conditional_target_still_waits_on_its_plain_edge_dependency() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+
+        // "finish" has a plain-edge dependency on "c" (via z -> c -> finish)
+        // *and* an incoming forward conditional edge from "start" that fires
+        // after the first wave, before "c" has run. "finish" must not start until "c"'s
+        // write has actually landed, regardless of which edge made it ready.
+        let c_done = Arc::new(AtomicBool::new(false));
+        let finish_saw_c_done = Arc::new(AtomicBool::new(false));
+
+        let c_done_clone = Arc::clone(&c_done);
+        let c = FunctionNode::new(Box::new(move |_| {
+            std::thread::sleep(Duration::from_millis(50));
+            c_done_clone.store(true, Ordering::SeqCst);
+            Ok(())
+        }));
+
+        let finish_saw_clone = Arc::clone(&finish_saw_c_done);
+        let c_done_check = Arc::clone(&c_done);
+        let finish = FunctionNode::new(Box::new(move |_| {
+            finish_saw_clone.store(c_done_check.load(Ordering::SeqCst), Ordering::SeqCst);
+            Ok(())
+        }));
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node("z".to_string(), noop_node());
+        builder.add_node("c".to_string(), Box::new(c));
+        builder.add_node("start".to_string(), noop_node());
+        builder.add_node("finish".to_string(), Box::new(finish));
+        builder.add_edge(("z".to_string(), "c".to_string()));
+        builder.add_edge(("c".to_string(), "finish".to_string()));
+        builder.add_conditional_edge(
+            "start".to_string(),
+            Box::new(|_state: &State| true),
+            "finish".to_string(),
+        );
+        let graph = builder.build();
+
+        graph.run().await.unwrap();
+
+        assert!(finish_saw_c_done.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn graph_run_independent_nodes_do_overlap_in_the_same_wave() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        // Two independent root nodes, neither `mark_solo`'d - this is the
+        // positive case `graph_run_mark_solo_never_overlaps_a_same_wave_sibling`
+        // guards against regressing into: without real parallelism neither
+        // test would distinguish "runs concurrently" from "runs one after
+        // the other", so this one asserts the two plain nodes genuinely do
+        // overlap. `Graph::run` bounds concurrency by the machine's
+        // available parallelism (see its doc comment), so on a single-core
+        // host two nodes can never overlap no matter how correct the
+        // scheduling is - skip there instead of asserting something the
+        // design doesn't promise.
+        if std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1) < 2 {
+            return;
+        }
+
+        let concurrent = Arc::new(AtomicUsize::new(0));
+        let max_concurrent = Arc::new(AtomicUsize::new(0));
+
+        let make_node = |concurrent: Arc<AtomicUsize>, max_concurrent: Arc<AtomicUsize>| {
+            FunctionNode::new(Box::new(move |_| {
+                let now = concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+                max_concurrent.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(30));
+                concurrent.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            }))
+        };
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node(
+            "a".to_string(),
+            Box::new(make_node(Arc::clone(&concurrent), Arc::clone(&max_concurrent))),
+        );
+        builder.add_node(
+            "b".to_string(),
+            Box::new(make_node(Arc::clone(&concurrent), Arc::clone(&max_concurrent))),
+        );
+        let graph = builder.build();
+
+        graph.run().await.unwrap();
+
+        assert_eq!(max_concurrent.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn graph_run_mark_solo_never_overlaps_a_same_wave_sibling() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        // Two independent root nodes land in the same wave - one plain, one
+        // `mark_solo`'d. The plain node holds `concurrent` up for long enough
+        // that, if the solo node ran without waiting for it, both would be
+        // "in flight" at once and `max_concurrent` would observe 2.
+        let concurrent = Arc::new(AtomicUsize::new(0));
+        let max_concurrent = Arc::new(AtomicUsize::new(0));
+
+        let slow_concurrent = Arc::clone(&concurrent);
+        let slow_max = Arc::clone(&max_concurrent);
+        let slow = FunctionNode::new(Box::new(move |_| {
+            let now = slow_concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+            slow_max.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(30));
+            slow_concurrent.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }));
+
+        let solo_concurrent = Arc::clone(&concurrent);
+        let solo_max = Arc::clone(&max_concurrent);
+        let solo = FunctionNode::new(Box::new(move |_| {
+            let now = solo_concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+            solo_max.fetch_max(now, Ordering::SeqCst);
+            solo_concurrent.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }));
+
+        let mut builder = GraphBuilder::new();
+        builder.add_node("slow".to_string(), Box::new(slow));
+        builder.add_node("solo".to_string(), Box::new(solo));
+        builder.mark_solo("solo".to_string());
+        let graph = builder.build();
+
+        graph.run().await.unwrap();
+
+        assert_eq!(max_concurrent.load(Ordering::SeqCst), 1);
+    }
+}